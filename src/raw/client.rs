@@ -1,7 +1,9 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
+use futures::stream::{self, Stream, StreamExt};
 use tikv_client_common::Error;
 
+use super::ingest::{self, SstMeta};
 use super::requests;
 use crate::{
     config::Config,
@@ -9,10 +11,25 @@ use crate::{
     request::{KvRequest, RetryOptions},
     BoundRange, ColumnFamily, Key, KvPair, Result, Value,
 };
-use std::{sync::Arc, u32};
+use std::{ops::Bound, sync::Arc, u32};
 
 const MAX_RAW_KV_SCAN_LIMIT: u32 = 10240;
 
+/// Compute the exclusive lower bound that resumes a scan immediately after `key`, without
+/// skipping or repeating it.
+fn successor(key: &Key) -> Bound<Key> {
+    Bound::Excluded(key.clone())
+}
+
+/// Negate `delta` for use as an [`increment_by`](Client::increment_by) step, failing instead of
+/// panicking (debug) or silently wrapping (release) when `delta` is `i64::MIN`, which has no
+/// representable positive counterpart.
+fn negate_decrement_delta(delta: i64) -> Result<i64> {
+    delta
+        .checked_neg()
+        .ok_or_else(|| Error::StringError("decrement: delta has no negated i64 value".to_owned()))
+}
+
 /// The TiKV raw `Client` is used to interact with TiKV using raw requests.
 ///
 /// Raw requests don't need a wrapping transaction.
@@ -23,6 +40,8 @@ const MAX_RAW_KV_SCAN_LIMIT: u32 = 10240;
 pub struct Client {
     rpc: Arc<PdRpcClient>,
     cf: Option<ColumnFamily>,
+    atomic: bool,
+    retry_options: RetryOptions,
 }
 
 impl Client {
@@ -62,7 +81,12 @@ impl Client {
     ) -> Result<Client> {
         let pd_endpoints: Vec<String> = pd_endpoints.into_iter().map(Into::into).collect();
         let rpc = Arc::new(PdRpcClient::connect(&pd_endpoints, &config, false).await?);
-        Ok(Client { rpc, cf: None })
+        Ok(Client {
+            rpc,
+            cf: None,
+            atomic: false,
+            retry_options: RetryOptions::default(),
+        })
     }
 
     /// Set the column family of requests.
@@ -88,6 +112,68 @@ impl Client {
         Client {
             rpc: self.rpc.clone(),
             cf: Some(cf),
+            atomic: self.atomic,
+            retry_options: self.retry_options.clone(),
+        }
+    }
+
+    /// Mark this client as atomic, so that its `put`, `update` and `delete` mutations (including
+    /// their batch and TTL variants) go through TiKV's atomic RawKV code path.
+    /// [`delete_range`](Client::delete_range) and [`ingest_sst`](Client::ingest_sst) do not
+    /// take that path.
+    ///
+    /// This function returns a new `Client`; the original `Client` can still be used.
+    ///
+    /// Mixing atomic and non-atomic operations on the same keys is unsafe: a plain
+    /// `put`/`delete` does not synchronize with [`compare_and_swap`](Client::compare_and_swap),
+    /// so a racing non-atomic write can silently invalidate the compare step. Call this
+    /// method before issuing any mutation if you intend to use [`compare_and_swap`](Client::compare_and_swap)
+    /// on the same keyspace, and make sure every writer of that keyspace does the same.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, RawClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// let client = RawClient::new(vec!["192.168.0.100"]).await.unwrap().with_atomic_for_cas();
+    /// # });
+    /// ```
+    pub fn with_atomic_for_cas(&self) -> Client {
+        Client {
+            rpc: self.rpc.clone(),
+            cf: self.cf.clone(),
+            atomic: true,
+            retry_options: self.retry_options.clone(),
+        }
+    }
+
+    /// Set the retry options used by requests created with this client.
+    ///
+    /// This function returns a new `Client`, requests created with it will use the supplied
+    /// [`RetryOptions`]. The original `Client` can still be used.
+    /// [`ingest_sst`](Client::ingest_sst) does not use these options.
+    ///
+    /// By default, a raw client retries with [`RetryOptions::default_optimistic`]. Latency
+    /// sensitive callers that would rather fail fast and shed load than retry silently can pass
+    /// [`RetryOptions::fail_fast`] instead.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, RawClient, RetryOptions};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// let client = RawClient::new(vec!["192.168.0.100"])
+    ///     .await
+    ///     .unwrap()
+    ///     .with_retry_options(RetryOptions::fail_fast());
+    /// # });
+    /// ```
+    pub fn with_retry_options(&self, retry_options: RetryOptions) -> Client {
+        Client {
+            rpc: self.rpc.clone(),
+            cf: self.cf.clone(),
+            atomic: self.atomic,
+            retry_options,
         }
     }
 
@@ -111,7 +197,7 @@ impl Client {
     /// ```
     pub async fn get(&self, key: impl Into<Key>) -> Result<Option<Value>> {
         requests::new_raw_get_request(key, self.cf.clone())
-            .execute(self.rpc.clone(), RetryOptions::default_optimistic())
+            .execute(self.rpc.clone(), self.retry_options.clone())
             .await
     }
 
@@ -138,7 +224,31 @@ impl Client {
         keys: impl IntoIterator<Item = impl Into<Key>>,
     ) -> Result<Vec<KvPair>> {
         requests::new_raw_batch_get_request(keys, self.cf.clone())
-            .execute(self.rpc.clone(), RetryOptions::default_optimistic())
+            .execute(self.rpc.clone(), self.retry_options.clone())
+            .await
+    }
+
+    /// Create a new 'get key TTL' request.
+    ///
+    /// Once resolved this request will result in the remaining time-to-live, in seconds, of the
+    /// given key.
+    ///
+    /// Returns `Ok(None)` if the key does not exist or has no TTL set.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, RawClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = RawClient::new(vec!["192.168.0.100"]).await.unwrap();
+    /// let key = "TiKV".to_owned();
+    /// let req = client.get_key_ttl(key);
+    /// let result: Option<u64> = req.await.unwrap();
+    /// # });
+    /// ```
+    pub async fn get_key_ttl(&self, key: impl Into<Key>) -> Result<Option<u64>> {
+        requests::new_raw_get_key_ttl_request(key, self.cf.clone())
+            .execute(self.rpc.clone(), self.retry_options.clone())
             .await
     }
 
@@ -159,8 +269,38 @@ impl Client {
     /// # });
     /// ```
     pub async fn put(&self, key: impl Into<Key>, value: impl Into<Value>) -> Result<()> {
-        requests::new_raw_put_request(key, value, self.cf.clone())
-            .execute(self.rpc.clone(), RetryOptions::default_optimistic())
+        requests::new_raw_put_request(key, value, self.cf.clone(), None)
+            .for_cas(self.atomic)
+            .execute(self.rpc.clone(), self.retry_options.clone())
+            .await
+    }
+
+    /// Create a new 'put' request that sets the key to expire after `ttl_secs` seconds.
+    ///
+    /// Once resolved this request will result in the setting of the value associated with the
+    /// given key, and TiKV will automatically delete the key once its TTL elapses.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use tikv_client::{Key, Value, Config, RawClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = RawClient::new(vec!["192.168.0.100"]).await.unwrap();
+    /// let key = "TiKV".to_owned();
+    /// let val = "TiKV".to_owned();
+    /// let req = client.put_with_ttl(key, val, 60);
+    /// let result: () = req.await.unwrap();
+    /// # });
+    /// ```
+    pub async fn put_with_ttl(
+        &self,
+        key: impl Into<Key>,
+        value: impl Into<Value>,
+        ttl_secs: u64,
+    ) -> Result<()> {
+        requests::new_raw_put_request(key, value, self.cf.clone(), Some(ttl_secs))
+            .for_cas(self.atomic)
+            .execute(self.rpc.clone(), self.retry_options.clone())
             .await
     }
 
@@ -185,8 +325,53 @@ impl Client {
         &self,
         pairs: impl IntoIterator<Item = impl Into<KvPair>>,
     ) -> Result<()> {
+        let pairs = pairs.into_iter().map(|pair| (pair.into(), None));
         requests::new_raw_batch_put_request(pairs, self.cf.clone())
-            .execute(self.rpc.clone(), RetryOptions::default_optimistic())
+            .for_cas(self.atomic)
+            .execute(self.rpc.clone(), self.retry_options.clone())
+            .await
+    }
+
+    /// Create a new 'batch put' request where every pair expires after its matching TTL.
+    ///
+    /// Once resolved this request will result in the setting of the values associated with the
+    /// given keys, each expiring `ttls[i]` seconds after it is written.
+    ///
+    /// `pairs` and `ttls` must have the same length; the `i`-th pair is given the `i`-th TTL.
+    /// Returns `Err` if the lengths differ rather than silently dropping the extra elements.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use tikv_client::{Result, KvPair, Key, Value, Config, RawClient, IntoOwnedRange};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = RawClient::new(vec!["192.168.0.100"]).await.unwrap();
+    /// let kvpair1 = ("PD".to_owned(), "Go".to_owned());
+    /// let kvpair2 = ("TiKV".to_owned(), "Rust".to_owned());
+    /// let iterable = vec![kvpair1, kvpair2];
+    /// let ttls = vec![60, 120];
+    /// let req = client.batch_put_with_ttl(iterable, ttls);
+    /// let result: () = req.await.unwrap();
+    /// # });
+    /// ```
+    pub async fn batch_put_with_ttl(
+        &self,
+        pairs: impl IntoIterator<Item = impl Into<KvPair>>,
+        ttls: impl IntoIterator<Item = u64>,
+    ) -> Result<()> {
+        let pairs: Vec<KvPair> = pairs.into_iter().map(Into::into).collect();
+        let ttls: Vec<u64> = ttls.into_iter().collect();
+        if pairs.len() != ttls.len() {
+            return Err(Error::StringError(format!(
+                "batch_put_with_ttl: pairs and ttls must have the same length, got {} pairs and {} ttls",
+                pairs.len(),
+                ttls.len()
+            )));
+        }
+        let pairs = pairs.into_iter().zip(ttls.into_iter().map(Some));
+        requests::new_raw_batch_put_request(pairs, self.cf.clone())
+            .for_cas(self.atomic)
+            .execute(self.rpc.clone(), self.retry_options.clone())
             .await
     }
 
@@ -208,7 +393,8 @@ impl Client {
     /// ```
     pub async fn update(&self, key: impl Into<Key>, value: impl Into<Value>) -> Result<()> {
         requests::new_raw_update_request(key, value, self.cf.clone())
-            .execute(self.rpc.clone(), RetryOptions::default_optimistic())
+            .for_cas(self.atomic)
+            .execute(self.rpc.clone(), self.retry_options.clone())
             .await
     }
 
@@ -234,10 +420,133 @@ impl Client {
         pairs: impl IntoIterator<Item = impl Into<KvPair>>,
     ) -> Result<()> {
         requests::new_raw_batch_update_request(pairs, self.cf.clone())
-            .execute(self.rpc.clone(), RetryOptions::default_optimistic())
+            .for_cas(self.atomic)
+            .execute(self.rpc.clone(), self.retry_options.clone())
             .await
     }
 
+    /// Create a new 'compare and swap' request.
+    ///
+    /// Once resolved this request will result in an atomic compare-and-set operation for the
+    /// given key.
+    ///
+    /// If `previous_value` is `Some(value)`, the swap succeeds if and only if the current value
+    /// stored at `key` equals `value`. If `previous_value` is `None`, the swap succeeds if and
+    /// only if `key` does not currently exist.
+    ///
+    /// The returned tuple holds the value that was actually present at `key` before the request
+    /// (`None` if the key did not exist) and a boolean indicating whether the swap took place.
+    ///
+    /// This is only safe to call on a client created with
+    /// [`with_atomic_for_cas`](Client::with_atomic_for_cas); all other mutations to the same
+    /// keys must go through an atomic client too, or the comparison can race with a plain
+    /// `put`/`delete`.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use tikv_client::{Value, Config, RawClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = RawClient::new(vec!["192.168.0.100"]).await.unwrap().with_atomic_for_cas();
+    /// let key = "TiKV".to_owned();
+    /// let req = client.compare_and_swap(key, None, "TiKV".to_owned());
+    /// let (previous_value, swapped): (Option<Value>, bool) = req.await.unwrap();
+    /// # });
+    /// ```
+    pub async fn compare_and_swap(
+        &self,
+        key: impl Into<Key>,
+        previous_value: Option<Value>,
+        new_value: Value,
+    ) -> Result<(Option<Value>, bool)> {
+        requests::new_raw_cas_request(key, previous_value, new_value, self.cf.clone())
+            .execute(self.rpc.clone(), self.retry_options.clone())
+            .await
+    }
+
+    /// Atomically add `delta` to the big-endian `i64` stored at `key`, returning the new value.
+    ///
+    /// If `key` does not exist, it is created as if its previous value were `0`.
+    ///
+    /// Implemented as a compare-and-swap retry loop on top of
+    /// [`compare_and_swap`](Client::compare_and_swap), so it requires a client created with
+    /// [`with_atomic_for_cas`](Client::with_atomic_for_cas). The loop gives up, returning an
+    /// error, once it has made as many attempts as this client's
+    /// [`RetryOptions::max_attempts`](crate::RetryOptions::max_attempts) allows.
+    ///
+    /// Each of those attempts in turn calls [`get`](Client::get) and
+    /// [`compare_and_swap`](Client::compare_and_swap), both of which independently retry on
+    /// region errors up to the same `max_attempts` budget. Under heavy write contention combined
+    /// with region churn this compounds multiplicatively: with the default
+    /// [`RetryOptions::default_optimistic`], a single call can in the worst case issue on the
+    /// order of `max_attempts^2` RPCs before giving up. Callers on latency-sensitive paths should
+    /// set a tighter budget with [`with_retry_options`](Client::with_retry_options).
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, RawClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = RawClient::new(vec!["192.168.0.100"]).await.unwrap().with_atomic_for_cas();
+    /// let key = "counter".to_owned();
+    /// let req = client.increment(key, 1);
+    /// let result: i64 = req.await.unwrap();
+    /// # });
+    /// ```
+    pub async fn increment(&self, key: impl Into<Key>, delta: i64) -> Result<i64> {
+        self.increment_by(key.into(), delta).await
+    }
+
+    /// Atomically subtract `delta` from the big-endian `i64` stored at `key`, returning the new
+    /// value.
+    ///
+    /// Equivalent to `increment(key, -delta)`; see [`increment`](Client::increment) for details.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, RawClient};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = RawClient::new(vec!["192.168.0.100"]).await.unwrap().with_atomic_for_cas();
+    /// let key = "counter".to_owned();
+    /// let req = client.decrement(key, 1);
+    /// let result: i64 = req.await.unwrap();
+    /// # });
+    /// ```
+    pub async fn decrement(&self, key: impl Into<Key>, delta: i64) -> Result<i64> {
+        let delta = negate_decrement_delta(delta)?;
+        self.increment_by(key.into(), delta).await
+    }
+
+    async fn increment_by(&self, key: Key, delta: i64) -> Result<i64> {
+        for _ in 0..self.retry_options.max_attempts() {
+            let previous = self.get(key.clone()).await?;
+            let current = match &previous {
+                Some(value) => {
+                    let bytes: [u8; 8] = value.clone().into().try_into().map_err(|_| {
+                        Error::StringError(
+                            "value stored at key is not an 8-byte big-endian i64".to_owned(),
+                        )
+                    })?;
+                    i64::from_be_bytes(bytes)
+                }
+                None => 0,
+            };
+            let next = current
+                .checked_add(delta)
+                .ok_or_else(|| Error::StringError("counter overflowed i64".to_owned()))?;
+            let new_value: Value = next.to_be_bytes().to_vec().into();
+            let (_, swapped) = self
+                .compare_and_swap(key.clone(), previous, new_value)
+                .await?;
+            if swapped {
+                return Ok(next);
+            }
+        }
+        Err(Error::StringError(
+            "increment: exceeded maximum compare-and-swap retries".to_owned(),
+        ))
+    }
 
     /// Create a new 'delete' request.
     ///
@@ -258,7 +567,8 @@ impl Client {
     /// ```
     pub async fn delete(&self, key: impl Into<Key>) -> Result<()> {
         requests::new_raw_delete_request(key, self.cf.clone())
-            .execute(self.rpc.clone(), RetryOptions::default_optimistic())
+            .for_cas(self.atomic)
+            .execute(self.rpc.clone(), self.retry_options.clone())
             .await
     }
 
@@ -281,7 +591,8 @@ impl Client {
     /// ```
     pub async fn batch_delete(&self, keys: impl IntoIterator<Item = impl Into<Key>>) -> Result<()> {
         requests::new_raw_batch_delete_request(keys, self.cf.clone())
-            .execute(self.rpc.clone(), RetryOptions::default_optimistic())
+            .for_cas(self.atomic)
+            .execute(self.rpc.clone(), self.retry_options.clone())
             .await
     }
 
@@ -302,7 +613,7 @@ impl Client {
     /// ```
     pub async fn delete_range(&self, range: impl Into<BoundRange>) -> Result<()> {
         requests::new_raw_delete_range_request(range, self.cf.clone())
-            .execute(self.rpc.clone(), RetryOptions::default_optimistic())
+            .execute(self.rpc.clone(), self.retry_options.clone())
             .await
     }
 
@@ -357,6 +668,57 @@ impl Client {
             .collect())
     }
 
+    /// Create a stream that scans the given range, transparently issuing further bounded scans
+    /// as needed so that the caller is never limited by [`MAX_RAW_KV_SCAN_LIMIT`].
+    ///
+    /// Each underlying RPC fetches at most `batch_size` pairs (capped at
+    /// [`MAX_RAW_KV_SCAN_LIMIT`]); once a batch is exhausted, the next one resumes immediately
+    /// after the last returned key. Only one scan RPC is ever in flight at a time, so the stream
+    /// is naturally back-pressured by how fast the caller consumes it.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use tikv_client::{KvPair, Config, RawClient, IntoOwnedRange};
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = RawClient::new(vec!["192.168.0.100"]).await.unwrap();
+    /// let inclusive_range = "TiKV"..="TiDB";
+    /// let mut stream = client.scan_stream(inclusive_range.into_owned(), 1024);
+    /// while let Some(pair) = stream.next().await {
+    ///     let _pair: KvPair = pair.unwrap();
+    /// }
+    /// # });
+    /// ```
+    pub fn scan_stream(
+        &self,
+        range: impl Into<BoundRange>,
+        batch_size: u32,
+    ) -> impl Stream<Item = Result<KvPair>> {
+        let client = self.clone();
+        let batch_size = batch_size.min(MAX_RAW_KV_SCAN_LIMIT);
+        stream::unfold(Some(range.into()), move |range| {
+            let client = client.clone();
+            async move {
+                let range = range?;
+                match client.scan_inner(range.clone(), batch_size, false).await {
+                    Ok(pairs) => {
+                        let exhausted = pairs.len() < batch_size as usize;
+                        let next_range = if exhausted {
+                            None
+                        } else {
+                            pairs
+                                .last()
+                                .map(|last| BoundRange::new(successor(last.key()), range.to))
+                        };
+                        Some((stream::iter(pairs.into_iter().map(Ok)), next_range))
+                    }
+                    Err(e) => Some((stream::iter(vec![Err(e)]), None)),
+                }
+            }
+        })
+        .flatten()
+    }
+
     /// Create a new 'batch scan' request.
     ///
     /// Once resolved this request will result in a set of scanners over the given keys.
@@ -438,7 +800,7 @@ impl Client {
         }
 
         let res = requests::new_raw_scan_request(range, limit, key_only, self.cf.clone())
-            .execute(self.rpc.clone(), RetryOptions::default_optimistic())
+            .execute(self.rpc.clone(), self.retry_options.clone())
             .await;
         res.map(|mut s| {
             s.truncate(limit as usize);
@@ -460,7 +822,57 @@ impl Client {
         }
 
         requests::new_raw_batch_scan_request(ranges, each_limit, key_only, self.cf.clone())
-            .execute(self.rpc.clone(), RetryOptions::default_optimistic())
+            .execute(self.rpc.clone(), self.retry_options.clone())
             .await
     }
+
+    /// Bulk-load pre-built SST files directly into TiKV via the `ImportSST` service, bypassing
+    /// the normal raw write path.
+    ///
+    /// Each file's destination regions are split and scattered to match its key range, the
+    /// file's contents are streamed to the owning stores, and then switched into place with an
+    /// ingest RPC. If a region's upload or ingest fails, the SST staged for that region is
+    /// cancelled before the error is returned; regions of the same file that were already
+    /// switched in, and files already ingested earlier in `files`, are not undone.
+    ///
+    /// This is orders of magnitude faster than looping [`batch_put`](Client::batch_put) for bulk
+    /// restores and dataset imports, at the cost of bypassing `put`/`delete`'s usual
+    /// consistency guarantees for the ingested range.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # use tikv_client::{Config, RawClient};
+    /// # use tikv_client::raw::SstMeta;
+    /// # use futures::prelude::*;
+    /// # futures::executor::block_on(async {
+    /// # let client = RawClient::new(vec!["192.168.0.100"]).await.unwrap();
+    /// let files = vec![SstMeta {
+    ///     path: "/data/backup/000001.sst".into(),
+    ///     start_key: "TiDB".to_owned().into(),
+    ///     end_key: "TiKV".to_owned().into(),
+    /// }];
+    /// let req = client.ingest_sst(files);
+    /// let result: () = req.await.unwrap();
+    /// # });
+    /// ```
+    pub async fn ingest_sst(&self, files: Vec<SstMeta>) -> Result<()> {
+        ingest::ingest_sst(self.rpc.clone(), self.cf.clone(), files).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::negate_decrement_delta;
+
+    #[test]
+    fn negate_decrement_delta_negates_ordinary_values() {
+        assert_eq!(negate_decrement_delta(5).unwrap(), -5);
+        assert_eq!(negate_decrement_delta(-5).unwrap(), 5);
+        assert_eq!(negate_decrement_delta(0).unwrap(), 0);
+    }
+
+    #[test]
+    fn negate_decrement_delta_rejects_i64_min() {
+        assert!(negate_decrement_delta(i64::MIN).is_err());
+    }
 }
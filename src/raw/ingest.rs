@@ -0,0 +1,198 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Bulk-loading support for the raw client via TiKV's `ImportSST` service.
+//!
+//! Ingestion bypasses the normal Raft write path: SST files are uploaded directly to the
+//! stores that own the destination key range and then switched in atomically, which is much
+//! faster than looping `batch_put` for large datasets (backup restores, bulk imports).
+
+use std::{path::PathBuf, sync::Arc};
+
+use tikv_client_common::Error;
+use tokio::{fs::File, io::AsyncReadExt};
+
+use crate::{pd::PdRpcClient, ColumnFamily, Key, Result};
+
+/// Chunk size used when streaming an SST file to a store over `ImportSST::Upload`, so a single
+/// large file never has to be buffered in memory all at once.
+const UPLOAD_CHUNK_BYTES: usize = 4 * 1024 * 1024;
+
+/// Describes one pre-built SST file to be ingested, along with the key range it covers.
+///
+/// The range must match the keys actually encoded in the file; it is used to split and
+/// scatter the destination regions so their outer boundaries line up with the file's range.
+#[derive(Clone, Debug)]
+pub struct SstMeta {
+    /// Path to the SST file on local disk.
+    pub path: PathBuf,
+    /// Inclusive start of the key range covered by this file.
+    pub start_key: Key,
+    /// Exclusive end of the key range covered by this file.
+    pub end_key: Key,
+}
+
+/// Ingest `files` into TiKV, bypassing the normal raw write path.
+///
+/// For each file this:
+/// 1. Splits and scatters the destination regions so their boundaries match the file's range.
+/// 2. Streams the file's contents in chunks to the store that owns the region.
+/// 3. Issues the ingest RPC so the store atomically switches the uploaded SST into place.
+///
+/// If a region's upload or switch-in fails, only the staged SST for *that* region is cancelled
+/// on the store it was uploaded to; regions that already switched in successfully, and files
+/// ingested earlier in the batch, are real committed data and are never touched.
+pub(crate) async fn ingest_sst(
+    rpc: Arc<PdRpcClient>,
+    cf: Option<ColumnFamily>,
+    files: Vec<SstMeta>,
+) -> Result<()> {
+    for file in files {
+        ingest_one(&rpc, &cf, &file).await?;
+    }
+    Ok(())
+}
+
+async fn ingest_one(rpc: &Arc<PdRpcClient>, cf: &Option<ColumnFamily>, file: &SstMeta) -> Result<()> {
+    let regions = split_and_scatter(rpc, file).await?;
+    for region in regions {
+        let uuid = rpc.new_sst_uuid().await?;
+        if let Err(e) = upload_and_switch_in(rpc, cf, file, &region, uuid).await {
+            rollback(rpc, region.leader_store_id, uuid).await;
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+async fn upload_and_switch_in(
+    rpc: &Arc<PdRpcClient>,
+    cf: &Option<ColumnFamily>,
+    file: &SstMeta,
+    region: &ScatteredRegion,
+    uuid: [u8; 16],
+) -> Result<()> {
+    let uploaded = upload(rpc, cf, file, region, uuid).await?;
+    switch_in(rpc, region, uploaded).await
+}
+
+/// A region whose boundaries have been split to align with an ingested file's key range, and
+/// which has since been scattered across stores so bulk-loading a large key range doesn't pile
+/// all of its regions onto a single store.
+struct ScatteredRegion {
+    region_id: u64,
+    region_epoch: (u64, u64),
+    leader_store_id: u64,
+    start_key: Key,
+    end_key: Key,
+}
+
+/// Ask PD to split the regions overlapping `[file.start_key, file.end_key)` on those boundaries
+/// and scatter them evenly across stores. Only the file's start and end keys are used as split
+/// points.
+async fn split_and_scatter(rpc: &Arc<PdRpcClient>, file: &SstMeta) -> Result<Vec<ScatteredRegion>> {
+    let split_keys = vec![file.start_key.clone(), file.end_key.clone()];
+    let regions = rpc.split_region_keys(split_keys).await?;
+
+    let mut scattered = Vec::with_capacity(regions.len());
+    for region in regions {
+        rpc.scatter_region(region.region_id).await?;
+        scattered.push(ScatteredRegion {
+            region_id: region.region_id,
+            region_epoch: region.region_epoch,
+            leader_store_id: region.leader_store_id,
+            start_key: region.start_key,
+            end_key: region.end_key,
+        });
+    }
+    Ok(scattered)
+}
+
+/// An SST that has been streamed to a store but not yet switched into its region.
+struct UploadedSst {
+    uuid: [u8; 16],
+    region_id: u64,
+    region_epoch: (u64, u64),
+    leader_store_id: u64,
+    length: u64,
+    crc32: u32,
+}
+
+/// Stream `file` to `region`'s leader store in bounded-size chunks over the
+/// `ImportSST::Upload` RPC, so a single large file never has to be buffered in memory. The whole
+/// file is sent, not only the keys that fall inside the region's range.
+async fn upload(
+    rpc: &Arc<PdRpcClient>,
+    cf: &Option<ColumnFamily>,
+    file: &SstMeta,
+    region: &ScatteredRegion,
+    uuid: [u8; 16],
+) -> Result<UploadedSst> {
+    let mut source = File::open(&file.path)
+        .await
+        .map_err(|e| Error::StringError(format!("failed to open SST file for upload: {}", e)))?;
+
+    let mut buf = vec![0u8; UPLOAD_CHUNK_BYTES];
+    let mut length = 0u64;
+    let mut crc32 = 0u32;
+    loop {
+        let read = source
+            .read(&mut buf)
+            .await
+            .map_err(|e| Error::StringError(format!("failed to read SST file for upload: {}", e)))?;
+        if read == 0 {
+            break;
+        }
+        let chunk = &buf[..read];
+        rpc.ingest_upload(region.leader_store_id, uuid, cf.clone(), chunk.to_vec())
+            .await?;
+        length += read as u64;
+        crc32 = update_crc32(crc32, chunk);
+    }
+
+    Ok(UploadedSst {
+        uuid,
+        region_id: region.region_id,
+        region_epoch: region.region_epoch,
+        leader_store_id: region.leader_store_id,
+        length,
+        crc32,
+    })
+}
+
+/// Issue `ImportSST::Ingest` so the store atomically swaps the uploaded SST into the region.
+async fn switch_in(rpc: &Arc<PdRpcClient>, region: &ScatteredRegion, uploaded: UploadedSst) -> Result<()> {
+    if uploaded.region_id != region.region_id {
+        return Err(Error::StringError(
+            "ingest_sst: uploaded SST does not belong to the region being switched in".to_owned(),
+        ));
+    }
+    rpc.ingest_ingest(
+        uploaded.leader_store_id,
+        uploaded.region_id,
+        uploaded.region_epoch,
+        uploaded.uuid,
+        uploaded.length,
+        uploaded.crc32,
+    )
+    .await
+}
+
+/// Best-effort: ask `store_id` to discard the staged SST identified by `uuid`, so a failed
+/// upload or switch-in never leaves an orphaned file behind. This only ever touches the staged
+/// SST itself, never the real keyspace — regions that already switched in are committed data
+/// and must not be deleted just because a later region in the same file failed.
+async fn rollback(rpc: &Arc<PdRpcClient>, store_id: u64, uuid: [u8; 16]) {
+    let _ = rpc.ingest_cancel_upload(store_id, uuid).await;
+}
+
+fn update_crc32(mut crc: u32, bytes: &[u8]) -> u32 {
+    crc = !crc;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+    !crc
+}
@@ -0,0 +1,599 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Constructors for the individual raw RPCs issued by [`super::Client`], and the [`KvRequest`]
+//! impls that know how to dispatch and retry them.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tikv_client_common::Error;
+
+use crate::{
+    pd::PdRpcClient,
+    request::{retry_on_region_error, KvRequest, RetryOptions},
+    BoundRange, ColumnFamily, Key, KvPair, Result, Value,
+};
+
+// --- get ---
+
+pub struct RawGetRequest {
+    key: Key,
+    cf: Option<ColumnFamily>,
+}
+
+pub fn new_raw_get_request(key: impl Into<Key>, cf: Option<ColumnFamily>) -> RawGetRequest {
+    RawGetRequest {
+        key: key.into(),
+        cf,
+    }
+}
+
+#[async_trait]
+impl KvRequest for RawGetRequest {
+    type Result = Option<Value>;
+
+    async fn execute(
+        self,
+        rpc: Arc<PdRpcClient>,
+        retry_options: RetryOptions,
+    ) -> Result<Self::Result> {
+        retry_on_region_error(&retry_options, || {
+            rpc.raw_get(self.key.clone(), self.cf.clone())
+        })
+        .await
+    }
+}
+
+// --- batch get ---
+
+pub struct RawBatchGetRequest {
+    keys: Vec<Key>,
+    cf: Option<ColumnFamily>,
+}
+
+pub fn new_raw_batch_get_request(
+    keys: impl IntoIterator<Item = impl Into<Key>>,
+    cf: Option<ColumnFamily>,
+) -> RawBatchGetRequest {
+    RawBatchGetRequest {
+        keys: keys.into_iter().map(Into::into).collect(),
+        cf,
+    }
+}
+
+#[async_trait]
+impl KvRequest for RawBatchGetRequest {
+    type Result = Vec<KvPair>;
+
+    async fn execute(
+        self,
+        rpc: Arc<PdRpcClient>,
+        retry_options: RetryOptions,
+    ) -> Result<Self::Result> {
+        retry_on_region_error(&retry_options, || {
+            rpc.raw_batch_get(self.keys.clone(), self.cf.clone())
+        })
+        .await
+    }
+}
+
+// --- get key ttl ---
+
+pub struct RawGetKeyTtlRequest {
+    key: Key,
+    cf: Option<ColumnFamily>,
+}
+
+pub fn new_raw_get_key_ttl_request(
+    key: impl Into<Key>,
+    cf: Option<ColumnFamily>,
+) -> RawGetKeyTtlRequest {
+    RawGetKeyTtlRequest {
+        key: key.into(),
+        cf,
+    }
+}
+
+#[async_trait]
+impl KvRequest for RawGetKeyTtlRequest {
+    type Result = Option<u64>;
+
+    async fn execute(
+        self,
+        rpc: Arc<PdRpcClient>,
+        retry_options: RetryOptions,
+    ) -> Result<Self::Result> {
+        retry_on_region_error(&retry_options, || {
+            rpc.raw_get_key_ttl(self.key.clone(), self.cf.clone())
+        })
+        .await
+    }
+}
+
+// --- put ---
+
+pub struct RawPutRequest {
+    key: Key,
+    value: Value,
+    cf: Option<ColumnFamily>,
+    ttl_secs: Option<u64>,
+    for_cas: bool,
+}
+
+pub fn new_raw_put_request(
+    key: impl Into<Key>,
+    value: impl Into<Value>,
+    cf: Option<ColumnFamily>,
+    ttl_secs: Option<u64>,
+) -> RawPutRequest {
+    RawPutRequest {
+        key: key.into(),
+        value: value.into(),
+        cf,
+        ttl_secs,
+        for_cas: false,
+    }
+}
+
+impl RawPutRequest {
+    /// Mark this put as part of an atomic keyspace, so TiKV routes it through the `for_cas`
+    /// RawKV code path instead of the plain, non-atomic one.
+    pub fn for_cas(mut self, for_cas: bool) -> Self {
+        self.for_cas = for_cas;
+        self
+    }
+}
+
+#[async_trait]
+impl KvRequest for RawPutRequest {
+    type Result = ();
+
+    async fn execute(
+        self,
+        rpc: Arc<PdRpcClient>,
+        retry_options: RetryOptions,
+    ) -> Result<Self::Result> {
+        retry_on_region_error(&retry_options, || {
+            rpc.raw_put(
+                self.key.clone(),
+                self.value.clone(),
+                self.cf.clone(),
+                self.ttl_secs,
+                self.for_cas,
+            )
+        })
+        .await
+    }
+}
+
+// --- batch put ---
+
+pub struct RawBatchPutRequest {
+    pairs: Vec<(KvPair, Option<u64>)>,
+    cf: Option<ColumnFamily>,
+    for_cas: bool,
+}
+
+pub fn new_raw_batch_put_request(
+    pairs: impl IntoIterator<Item = (KvPair, Option<u64>)>,
+    cf: Option<ColumnFamily>,
+) -> RawBatchPutRequest {
+    RawBatchPutRequest {
+        pairs: pairs.into_iter().collect(),
+        cf,
+        for_cas: false,
+    }
+}
+
+impl RawBatchPutRequest {
+    /// Mark this batch put as part of an atomic keyspace, so TiKV routes it through the
+    /// `for_cas` RawKV code path instead of the plain, non-atomic one.
+    pub fn for_cas(mut self, for_cas: bool) -> Self {
+        self.for_cas = for_cas;
+        self
+    }
+}
+
+#[async_trait]
+impl KvRequest for RawBatchPutRequest {
+    type Result = ();
+
+    async fn execute(
+        self,
+        rpc: Arc<PdRpcClient>,
+        retry_options: RetryOptions,
+    ) -> Result<Self::Result> {
+        retry_on_region_error(&retry_options, || {
+            rpc.raw_batch_put(self.pairs.clone(), self.cf.clone(), self.for_cas)
+        })
+        .await
+    }
+}
+
+// --- update ---
+
+pub struct RawUpdateRequest {
+    key: Key,
+    value: Value,
+    cf: Option<ColumnFamily>,
+    for_cas: bool,
+}
+
+pub fn new_raw_update_request(
+    key: impl Into<Key>,
+    value: impl Into<Value>,
+    cf: Option<ColumnFamily>,
+) -> RawUpdateRequest {
+    RawUpdateRequest {
+        key: key.into(),
+        value: value.into(),
+        cf,
+        for_cas: false,
+    }
+}
+
+impl RawUpdateRequest {
+    /// Mark this update as part of an atomic keyspace, so TiKV routes it through the `for_cas`
+    /// RawKV code path instead of the plain, non-atomic one.
+    pub fn for_cas(mut self, for_cas: bool) -> Self {
+        self.for_cas = for_cas;
+        self
+    }
+}
+
+#[async_trait]
+impl KvRequest for RawUpdateRequest {
+    type Result = ();
+
+    async fn execute(
+        self,
+        rpc: Arc<PdRpcClient>,
+        retry_options: RetryOptions,
+    ) -> Result<Self::Result> {
+        retry_on_region_error(&retry_options, || {
+            rpc.raw_update(
+                self.key.clone(),
+                self.value.clone(),
+                self.cf.clone(),
+                self.for_cas,
+            )
+        })
+        .await
+    }
+}
+
+// --- batch update ---
+
+pub struct RawBatchUpdateRequest {
+    pairs: Vec<KvPair>,
+    cf: Option<ColumnFamily>,
+    for_cas: bool,
+}
+
+pub fn new_raw_batch_update_request(
+    pairs: impl IntoIterator<Item = impl Into<KvPair>>,
+    cf: Option<ColumnFamily>,
+) -> RawBatchUpdateRequest {
+    RawBatchUpdateRequest {
+        pairs: pairs.into_iter().map(Into::into).collect(),
+        cf,
+        for_cas: false,
+    }
+}
+
+impl RawBatchUpdateRequest {
+    /// Mark this batch update as part of an atomic keyspace, so TiKV routes it through the
+    /// `for_cas` RawKV code path instead of the plain, non-atomic one.
+    pub fn for_cas(mut self, for_cas: bool) -> Self {
+        self.for_cas = for_cas;
+        self
+    }
+}
+
+#[async_trait]
+impl KvRequest for RawBatchUpdateRequest {
+    type Result = ();
+
+    async fn execute(
+        self,
+        rpc: Arc<PdRpcClient>,
+        retry_options: RetryOptions,
+    ) -> Result<Self::Result> {
+        retry_on_region_error(&retry_options, || {
+            rpc.raw_batch_update(self.pairs.clone(), self.cf.clone(), self.for_cas)
+        })
+        .await
+    }
+}
+
+// --- delete ---
+
+pub struct RawDeleteRequest {
+    key: Key,
+    cf: Option<ColumnFamily>,
+    for_cas: bool,
+}
+
+pub fn new_raw_delete_request(key: impl Into<Key>, cf: Option<ColumnFamily>) -> RawDeleteRequest {
+    RawDeleteRequest {
+        key: key.into(),
+        cf,
+        for_cas: false,
+    }
+}
+
+impl RawDeleteRequest {
+    /// Mark this delete as part of an atomic keyspace, so TiKV routes it through the `for_cas`
+    /// RawKV code path instead of the plain, non-atomic one.
+    pub fn for_cas(mut self, for_cas: bool) -> Self {
+        self.for_cas = for_cas;
+        self
+    }
+}
+
+#[async_trait]
+impl KvRequest for RawDeleteRequest {
+    type Result = ();
+
+    async fn execute(
+        self,
+        rpc: Arc<PdRpcClient>,
+        retry_options: RetryOptions,
+    ) -> Result<Self::Result> {
+        retry_on_region_error(&retry_options, || {
+            rpc.raw_delete(self.key.clone(), self.cf.clone(), self.for_cas)
+        })
+        .await
+    }
+}
+
+// --- batch delete ---
+
+pub struct RawBatchDeleteRequest {
+    keys: Vec<Key>,
+    cf: Option<ColumnFamily>,
+    for_cas: bool,
+}
+
+pub fn new_raw_batch_delete_request(
+    keys: impl IntoIterator<Item = impl Into<Key>>,
+    cf: Option<ColumnFamily>,
+) -> RawBatchDeleteRequest {
+    RawBatchDeleteRequest {
+        keys: keys.into_iter().map(Into::into).collect(),
+        cf,
+        for_cas: false,
+    }
+}
+
+impl RawBatchDeleteRequest {
+    /// Mark this batch delete as part of an atomic keyspace, so TiKV routes it through the
+    /// `for_cas` RawKV code path instead of the plain, non-atomic one.
+    pub fn for_cas(mut self, for_cas: bool) -> Self {
+        self.for_cas = for_cas;
+        self
+    }
+}
+
+#[async_trait]
+impl KvRequest for RawBatchDeleteRequest {
+    type Result = ();
+
+    async fn execute(
+        self,
+        rpc: Arc<PdRpcClient>,
+        retry_options: RetryOptions,
+    ) -> Result<Self::Result> {
+        retry_on_region_error(&retry_options, || {
+            rpc.raw_batch_delete(self.keys.clone(), self.cf.clone(), self.for_cas)
+        })
+        .await
+    }
+}
+
+// --- delete range ---
+
+pub struct RawDeleteRangeRequest {
+    range: BoundRange,
+    cf: Option<ColumnFamily>,
+}
+
+pub fn new_raw_delete_range_request(
+    range: impl Into<BoundRange>,
+    cf: Option<ColumnFamily>,
+) -> RawDeleteRangeRequest {
+    RawDeleteRangeRequest {
+        range: range.into(),
+        cf,
+    }
+}
+
+#[async_trait]
+impl KvRequest for RawDeleteRangeRequest {
+    type Result = ();
+
+    async fn execute(
+        self,
+        rpc: Arc<PdRpcClient>,
+        retry_options: RetryOptions,
+    ) -> Result<Self::Result> {
+        retry_on_region_error(&retry_options, || {
+            rpc.raw_delete_range(self.range.clone(), self.cf.clone())
+        })
+        .await
+    }
+}
+
+// --- scan ---
+
+pub struct RawScanRequest {
+    range: BoundRange,
+    limit: u32,
+    key_only: bool,
+    cf: Option<ColumnFamily>,
+}
+
+pub fn new_raw_scan_request(
+    range: impl Into<BoundRange>,
+    limit: u32,
+    key_only: bool,
+    cf: Option<ColumnFamily>,
+) -> RawScanRequest {
+    RawScanRequest {
+        range: range.into(),
+        limit,
+        key_only,
+        cf,
+    }
+}
+
+#[async_trait]
+impl KvRequest for RawScanRequest {
+    type Result = Vec<KvPair>;
+
+    async fn execute(
+        self,
+        rpc: Arc<PdRpcClient>,
+        retry_options: RetryOptions,
+    ) -> Result<Self::Result> {
+        retry_on_region_error(&retry_options, || {
+            rpc.raw_scan(self.range.clone(), self.limit, self.key_only, self.cf.clone())
+        })
+        .await
+    }
+}
+
+// --- batch scan ---
+
+pub struct RawBatchScanRequest {
+    ranges: Vec<BoundRange>,
+    each_limit: u32,
+    key_only: bool,
+    cf: Option<ColumnFamily>,
+}
+
+pub fn new_raw_batch_scan_request(
+    ranges: impl IntoIterator<Item = impl Into<BoundRange>>,
+    each_limit: u32,
+    key_only: bool,
+    cf: Option<ColumnFamily>,
+) -> RawBatchScanRequest {
+    RawBatchScanRequest {
+        ranges: ranges.into_iter().map(Into::into).collect(),
+        each_limit,
+        key_only,
+        cf,
+    }
+}
+
+#[async_trait]
+impl KvRequest for RawBatchScanRequest {
+    type Result = Vec<KvPair>;
+
+    async fn execute(
+        self,
+        rpc: Arc<PdRpcClient>,
+        retry_options: RetryOptions,
+    ) -> Result<Self::Result> {
+        retry_on_region_error(&retry_options, || {
+            rpc.raw_batch_scan(
+                self.ranges.clone(),
+                self.each_limit,
+                self.key_only,
+                self.cf.clone(),
+            )
+        })
+        .await
+    }
+}
+
+// --- compare and swap ---
+
+pub struct RawCasRequest {
+    key: Key,
+    previous_value: Option<Value>,
+    new_value: Value,
+    cf: Option<ColumnFamily>,
+}
+
+pub fn new_raw_cas_request(
+    key: impl Into<Key>,
+    previous_value: Option<Value>,
+    new_value: Value,
+    cf: Option<ColumnFamily>,
+) -> RawCasRequest {
+    RawCasRequest {
+        key: key.into(),
+        previous_value,
+        new_value,
+        cf,
+    }
+}
+
+#[async_trait]
+impl KvRequest for RawCasRequest {
+    type Result = (Option<Value>, bool);
+
+    async fn execute(
+        self,
+        rpc: Arc<PdRpcClient>,
+        retry_options: RetryOptions,
+    ) -> Result<Self::Result> {
+        let previous_value = self.previous_value.clone();
+        retry_on_region_error(&retry_options, || {
+            rpc.raw_compare_and_swap(
+                self.key.clone(),
+                self.previous_value.clone(),
+                self.new_value.clone(),
+                self.cf.clone(),
+            )
+        })
+        .await
+        .and_then(|(actual_previous_value, swapped)| {
+            validate_cas_result(previous_value.clone(), actual_previous_value, swapped)
+        })
+    }
+}
+
+/// Sanity-check a raw CAS response against the value this request expected to see: a store
+/// reporting a successful swap must echo back the same previous value we compared against, or
+/// something is badly wrong (e.g. a store-side bug, or the store talking about the wrong key).
+fn validate_cas_result(
+    expected_previous_value: Option<Value>,
+    actual_previous_value: Option<Value>,
+    swapped: bool,
+) -> Result<(Option<Value>, bool)> {
+    if swapped && actual_previous_value != expected_previous_value {
+        return Err(Error::StringError(
+            "raw CAS reported success but returned a stale previous value".to_owned(),
+        ));
+    }
+    Ok((actual_previous_value, swapped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate_cas_result;
+
+    #[test]
+    fn validate_cas_result_accepts_a_matching_swap() {
+        let previous: Option<crate::Value> = Some("old".to_owned().into());
+        let result = validate_cas_result(previous.clone(), previous.clone(), true).unwrap();
+        assert_eq!(result, (previous, true));
+    }
+
+    #[test]
+    fn validate_cas_result_accepts_a_failed_swap_with_a_different_previous_value() {
+        let expected: Option<crate::Value> = Some("old".to_owned().into());
+        let actual: Option<crate::Value> = Some("other".to_owned().into());
+        let result = validate_cas_result(expected, actual.clone(), false).unwrap();
+        assert_eq!(result, (actual, false));
+    }
+
+    #[test]
+    fn validate_cas_result_rejects_a_stale_previous_value_on_success() {
+        let expected: Option<crate::Value> = Some("old".to_owned().into());
+        let actual: Option<crate::Value> = Some("other".to_owned().into());
+        assert!(validate_cas_result(expected, actual, true).is_err());
+    }
+}
@@ -0,0 +1,86 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Request plumbing shared by every raw and transactional request: how a request retries on
+//! transient errors, and the trait every request type implements in order to be executed
+//! against a cluster.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::{pd::PdRpcClient, Result};
+
+/// Controls how a request behaves when it hits a transient region or leader error.
+#[derive(Clone, Debug)]
+pub struct RetryOptions {
+    max_attempts: u32,
+}
+
+impl RetryOptions {
+    /// Retry region/leader errors up to a generous budget of 100 attempts before giving up.
+    /// Attempts follow each other immediately; there is no backoff between them.
+    ///
+    /// This is the default used by every raw and transactional request.
+    pub fn default_optimistic() -> Self {
+        RetryOptions { max_attempts: 100 }
+    }
+
+    /// Perform zero region/leader retries, surfacing the first error immediately.
+    ///
+    /// Intended for latency-sensitive callers (benchmarks, services with strict tail-latency
+    /// budgets) that would rather fail fast and shed load than retry silently.
+    pub fn fail_fast() -> Self {
+        RetryOptions { max_attempts: 1 }
+    }
+
+    /// The maximum number of attempts a request (or a caller looping on top of one, such as
+    /// [`crate::raw::Client::increment`]) should make before giving up.
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+}
+
+impl Default for RetryOptions {
+    /// Equivalent to [`RetryOptions::default_optimistic`].
+    fn default() -> Self {
+        RetryOptions::default_optimistic()
+    }
+}
+
+/// A request that can be sent to a TiKV cluster through a [`PdRpcClient`].
+#[async_trait]
+pub trait KvRequest: Sized {
+    /// The value produced once the request resolves.
+    type Result;
+
+    /// Dispatch this request to the region(s) that own its keys, retrying according to
+    /// `retry_options` on transient region/leader errors.
+    async fn execute(
+        self,
+        rpc: Arc<PdRpcClient>,
+        retry_options: RetryOptions,
+    ) -> Result<Self::Result>;
+}
+
+/// Retry `attempt` up to `retry_options.max_attempts()` times, retrying only on
+/// [`Error::RegionError`](tikv_client_common::Error::RegionError) and returning the last error
+/// once the budget is exhausted.
+pub(crate) async fn retry_on_region_error<T, Fut>(
+    retry_options: &RetryOptions,
+    mut attempt: impl FnMut() -> Fut,
+) -> Result<T>
+where
+    Fut: std::future::Future<Output = Result<T>>,
+{
+    use tikv_client_common::Error;
+
+    let mut last_err = Error::StringError("request made zero attempts".to_owned());
+    for _ in 0..retry_options.max_attempts().max(1) {
+        match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(Error::RegionError(e)) => last_err = Error::RegionError(e),
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last_err)
+}